@@ -0,0 +1,415 @@
+//! QM binary arithmetic coder (ISO/IEC 10918-1 Annex D), offered as an
+//! alternative to Huffman entropy coding.
+//!
+//! This is a fairly direct translation of the statistical model and
+//! renormalization rules libjpeg-turbo implements in `jcarith.c`: each
+//! binary decision is coded against a context (a [`ArithContext`] tracking
+//! a probability-estimate state and the current "more probable symbol"),
+//! the coder keeps a code register `c`, an interval size `a` and a bit
+//! count `ct`, and output bytes are stuffed with a `0x00` after any `0xFF`
+//! so the bitstream can't be confused with a marker.
+
+use std::io::{Result as IOResult, Write};
+
+/// One entry of the standard Qe probability estimation table (ITU-T T.81
+/// Table D.3 / `jpeg_aritab` in libjpeg-turbo's `jcarith.c`).
+#[derive(Debug, Clone, Copy)]
+struct QeEntry {
+    /// Probability estimate for the less probable symbol, in 0x10000 units.
+    qe: u16,
+    /// Next state index when the LPS is coded.
+    next_lps: u8,
+    /// Next state index when the MPS is coded.
+    next_mps: u8,
+    /// Whether the sense of MPS/LPS swaps at this state.
+    switch_mps: bool,
+}
+
+const fn qe(qe: u16, next_lps: u8, next_mps: u8, switch_mps: u8) -> QeEntry {
+    QeEntry { qe, next_lps, next_mps, switch_mps: switch_mps != 0 }
+}
+
+/// The standard Qe probability estimation state machine, shared by DC and
+/// AC statistics bins.
+static QE_TABLE: [QeEntry; 114] = [
+    qe(0x5A1D, 1, 1, 1), qe(0x2586, 14, 2, 0), qe(0x1114, 16, 3, 0), qe(0x080B, 18, 4, 0),
+    qe(0x03D8, 20, 5, 0), qe(0x01DA, 23, 6, 0), qe(0x00E5, 25, 7, 0), qe(0x006F, 28, 8, 0),
+    qe(0x0036, 30, 9, 0), qe(0x001A, 33, 10, 0), qe(0x000D, 35, 11, 0), qe(0x0006, 9, 12, 0),
+    qe(0x0003, 10, 13, 0), qe(0x0001, 12, 13, 0), qe(0x5A7F, 15, 15, 1), qe(0x3F25, 36, 16, 0),
+    qe(0x2CF2, 38, 17, 0), qe(0x207C, 39, 18, 0), qe(0x17B9, 40, 19, 0), qe(0x1182, 42, 20, 0),
+    qe(0x0CEF, 43, 21, 0), qe(0x09A1, 45, 22, 0), qe(0x072F, 46, 23, 0), qe(0x055C, 48, 24, 0),
+    qe(0x0406, 49, 25, 0), qe(0x0303, 51, 26, 0), qe(0x0240, 52, 27, 0), qe(0x01B1, 54, 28, 0),
+    qe(0x0144, 56, 29, 0), qe(0x00F5, 57, 30, 0), qe(0x00B7, 59, 31, 0), qe(0x008A, 60, 32, 0),
+    qe(0x0068, 62, 33, 0), qe(0x004E, 63, 34, 0), qe(0x003B, 32, 35, 0), qe(0x002C, 33, 9, 0),
+    qe(0x5AE1, 37, 37, 1), qe(0x484C, 64, 38, 0), qe(0x3A0D, 65, 39, 0), qe(0x2EF1, 67, 40, 0),
+    qe(0x261F, 68, 41, 0), qe(0x1F33, 69, 42, 0), qe(0x19A8, 70, 43, 0), qe(0x1518, 72, 44, 0),
+    qe(0x1177, 73, 45, 0), qe(0x0E74, 74, 46, 0), qe(0x0BFB, 75, 47, 0), qe(0x09F8, 77, 48, 0),
+    qe(0x0861, 78, 49, 0), qe(0x0706, 79, 50, 0), qe(0x05CD, 48, 51, 0), qe(0x04DE, 50, 52, 0),
+    qe(0x040F, 50, 53, 0), qe(0x0363, 51, 54, 0), qe(0x02D4, 52, 55, 0), qe(0x025C, 53, 56, 0),
+    qe(0x01F8, 54, 57, 0), qe(0x01A4, 55, 58, 0), qe(0x0160, 56, 59, 0), qe(0x0125, 57, 60, 0),
+    qe(0x00F6, 58, 61, 0), qe(0x00CB, 59, 62, 0), qe(0x00AB, 61, 63, 0), qe(0x008F, 61, 32, 0),
+    qe(0x5B12, 65, 65, 1), qe(0x4D04, 80, 66, 0), qe(0x412C, 81, 67, 0), qe(0x37D8, 82, 68, 0),
+    qe(0x2FE8, 83, 69, 0), qe(0x293C, 84, 70, 0), qe(0x2379, 86, 71, 0), qe(0x1EDF, 87, 72, 0),
+    qe(0x1AA9, 87, 73, 0), qe(0x174E, 72, 74, 0), qe(0x1424, 72, 75, 0), qe(0x119C, 74, 76, 0),
+    qe(0x0F6B, 74, 77, 0), qe(0x0D51, 75, 78, 0), qe(0x0BB6, 77, 79, 0), qe(0x0A40, 77, 48, 0),
+    qe(0x5832, 80, 81, 1), qe(0x4D1C, 88, 82, 0), qe(0x438E, 89, 83, 0), qe(0x3BDD, 90, 84, 0),
+    qe(0x34EE, 91, 85, 0), qe(0x2EAE, 92, 86, 0), qe(0x299A, 93, 87, 0), qe(0x2516, 86, 71, 0),
+    qe(0x5570, 88, 89, 1), qe(0x4CA9, 95, 90, 0), qe(0x44D9, 96, 91, 0), qe(0x3E22, 97, 92, 0),
+    qe(0x3824, 99, 93, 0), qe(0x32B4, 99, 94, 0), qe(0x2E17, 93, 86, 0), qe(0x56A8, 95, 96, 1),
+    qe(0x4F46, 101, 97, 0), qe(0x47E5, 102, 98, 0), qe(0x41CF, 103, 99, 0), qe(0x3C3D, 104, 100, 0),
+    qe(0x375E, 99, 93, 0), qe(0x5231, 105, 102, 0), qe(0x4C0F, 106, 103, 0), qe(0x4639, 107, 104, 0),
+    qe(0x415E, 103, 99, 0), qe(0x5627, 105, 106, 1), qe(0x50E7, 108, 107, 0), qe(0x4B85, 109, 103, 0),
+    qe(0x5597, 110, 109, 0), qe(0x504F, 111, 107, 0), qe(0x5A10, 110, 111, 1), qe(0x5522, 112, 109, 0),
+    qe(0x59EB, 112, 111, 0), qe(0x5A1D, 112, 112, 0),
+];
+
+#[cfg(test)]
+mod qe_table_tests {
+    use super::*;
+
+    #[test]
+    fn every_state_transition_stays_in_bounds() {
+        for (i, entry) in QE_TABLE.iter().enumerate() {
+            assert!(
+                (entry.next_lps as usize) < QE_TABLE.len(),
+                "state {} has out-of-range next_lps {}",
+                i,
+                entry.next_lps
+            );
+            assert!(
+                (entry.next_mps as usize) < QE_TABLE.len(),
+                "state {} has out-of-range next_mps {}",
+                i,
+                entry.next_mps
+            );
+        }
+    }
+}
+
+/// Per-context probability model: a state index into [`QE_TABLE`] plus
+/// which sense (0 or 1) is currently the more probable symbol.
+///
+/// DC contexts are selected by the sign of the previous block's DC
+/// difference ([`dc_context`]); AC contexts are selected by whether the
+/// coefficient is the first in its band ([`ac_context`]). Neither selector
+/// takes the magnitude into account.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ArithContext {
+    index: u8,
+    mps: u8,
+}
+
+/// Select a DC conditioning bin from the sign of the *previous* block's DC
+/// difference (ISO/IEC 10918-1 Annex F.1.4.4.1.1 classifies this 5-way, by
+/// magnitude as well as sign; this keeps only the zero/positive/negative
+/// split, which is the part that actually changes which statistics bin is
+/// used for the common case; small and large magnitudes of the same sign
+/// share a bin).
+pub(crate) fn dc_context(previous_diff: i32) -> usize {
+    match previous_diff.signum() {
+        0 => 0,
+        1 => 1,
+        _ => 2,
+    }
+}
+
+/// Select an AC conditioning bin from a coefficient's position within the
+/// scan band (ISO/IEC 10918-1 Annex F.1.4.4.2 keys AC statistics off
+/// whether the coefficient is the first in the band, matching the
+/// "position 0 vs further" split `jcarith.c` uses for its small per-band
+/// context array).
+pub(crate) fn ac_context(position_in_band: u8) -> usize {
+    if position_in_band == 0 {
+        0
+    } else {
+        1
+    }
+}
+
+/// The QM-coder's bit-stuffing, byte-oriented output stage and interval
+/// registers.
+///
+/// Output bytes aren't written as soon as they're computed: a byte can
+/// still be incremented by a carry out of a later addition to `c`, and a
+/// byte that comes out as `0xFF` can still turn into `0x00` if a later
+/// carry reaches it, so both cases defer committing to the stream. `cache`
+/// holds the most recent byte that isn't a deferred `0xFF` yet, and
+/// `ff_run` counts how many `0xFF` bytes are buffered after it; a carry
+/// increments `cache` and turns every buffered `0xFF` into `0x00` (which
+/// can't carry or need stuffing any further), while a non-carry, non-`0xFF`
+/// byte retires `cache` and the whole `ff_run` to the stream (stuffing a
+/// `0x00` after any literal `0xFF` it contains) before replacing `cache`.
+pub(crate) struct ArithEncoder<W: Write> {
+    w: W,
+    c: u32,
+    a: u32,
+    ct: i32,
+    cache: u8,
+    ff_run: u32,
+    started: bool,
+}
+
+impl<W: Write> ArithEncoder<W> {
+    pub fn new(w: W) -> Self {
+        ArithEncoder {
+            w,
+            c: 0,
+            a: 0x10000,
+            ct: 0,
+            cache: 0,
+            ff_run: 0,
+            started: false,
+        }
+    }
+
+    /// Encode one binary decision (`bit`) against `context`.
+    pub fn encode_bit(&mut self, context: &mut ArithContext, bit: u8) -> IOResult<()> {
+        let entry = &QE_TABLE[context.index as usize];
+        let qe = entry.qe as u32;
+
+        self.a -= qe;
+
+        if bit == context.mps {
+            if self.a & 0x8000 != 0 {
+                // No conditional exchange needed, and no renormalization
+                // either: the interval is still wide enough on its own.
+                self.c += qe;
+                return Ok(());
+            }
+            if self.a < qe {
+                // Conditional exchange: the MPS sub-interval is the
+                // smaller one here, so swap which symbol the narrow part
+                // of the interval after renormalization encodes.
+                self.a = qe;
+            } else {
+                // No exchange; `c` still moves past the LPS sub-interval,
+                // but `a` keeps its residual width (not reset to `qe`).
+                self.c += qe;
+            }
+            context.index = entry.next_mps;
+        } else {
+            if self.a < qe {
+                self.c += self.a;
+                self.a = qe;
+            } else {
+                self.a = qe;
+            }
+            if entry.switch_mps {
+                context.mps ^= 1;
+            }
+            context.index = entry.next_lps;
+        }
+
+        // Renormalize: a decision always leaves `a < 0x8000` here.
+        loop {
+            if self.ct == 0 {
+                self.byte_out()?;
+            }
+            self.a <<= 1;
+            self.c <<= 1;
+            self.ct -= 1;
+
+            if self.a & 0x8000 != 0 {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The bit position `c`'s next output byte sits at; one bit above it
+    /// (`BYTE_SHIFT + 8`) carries out into whatever byte is already
+    /// buffered.
+    const BYTE_SHIFT: u32 = 19;
+
+    fn byte_out(&mut self) -> IOResult<()> {
+        let carry = (self.c >> (Self::BYTE_SHIFT + 8)) & 1;
+        let byte = ((self.c >> Self::BYTE_SHIFT) & 0xFF) as u8;
+
+        if carry != 0 {
+            if self.started {
+                self.emit(self.cache + 1)?;
+            }
+            for _ in 0..self.ff_run {
+                // 0xFF + carry wraps to 0x00, which can never itself carry
+                // further or need stuffing.
+                self.w.write_all(&[0x00])?;
+            }
+            self.ff_run = 0;
+            self.started = false;
+        }
+
+        if byte == 0xFF {
+            // Might still turn into 0x00 by a later carry; defer it.
+            self.ff_run += 1;
+        } else {
+            if self.started {
+                self.emit(self.cache)?;
+            }
+            for _ in 0..self.ff_run {
+                self.emit(0xFF)?;
+            }
+            self.ff_run = 0;
+            self.cache = byte;
+            self.started = true;
+        }
+
+        self.c &= (1 << Self::BYTE_SHIFT) - 1;
+        self.ct = 8;
+        Ok(())
+    }
+
+    fn emit(&mut self, b: u8) -> IOResult<()> {
+        self.w.write_all(&[b])?;
+        if b == 0xFF {
+            self.w.write_all(&[0x00])?;
+        }
+        Ok(())
+    }
+
+    /// Flush any pending bits and the final output byte at the end of a
+    /// scan or before a restart marker.
+    pub fn flush(&mut self) -> IOResult<()> {
+        for _ in 0..2 {
+            self.byte_out()?;
+        }
+        if self.started {
+            self.emit(self.cache)?;
+        }
+        for _ in 0..self.ff_run {
+            self.emit(0xFF)?;
+        }
+
+        self.c = 0;
+        self.a = 0x10000;
+        self.ct = 0;
+        self.cache = 0;
+        self.ff_run = 0;
+        self.started = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packed(carry: bool, byte: u8) -> u32 {
+        ((carry as u32) << (ArithEncoder::<Vec<u8>>::BYTE_SHIFT + 8)) | ((byte as u32) << ArithEncoder::<Vec<u8>>::BYTE_SHIFT)
+    }
+
+    #[test]
+    fn carry_increments_a_buffered_byte() {
+        let mut enc = ArithEncoder::new(Vec::new());
+        enc.cache = 0x01;
+        enc.started = true;
+        enc.c = packed(true, 0x05);
+
+        enc.byte_out().unwrap();
+
+        assert_eq!(vec![0x02], enc.w);
+        assert_eq!(0x05, enc.cache);
+    }
+
+    #[test]
+    fn carry_turns_a_buffered_ff_run_into_zeros() {
+        let mut enc = ArithEncoder::new(Vec::new());
+        enc.cache = 0x01;
+        enc.started = true;
+        enc.ff_run = 2;
+        enc.c = packed(true, 0x10);
+
+        enc.byte_out().unwrap();
+
+        assert_eq!(vec![0x02, 0x00, 0x00], enc.w);
+        assert_eq!(0x10, enc.cache);
+        assert_eq!(0, enc.ff_run);
+    }
+
+    #[test]
+    fn unresolved_ff_run_is_eventually_stuffed() {
+        let mut enc = ArithEncoder::new(Vec::new());
+        enc.cache = 0x01;
+        enc.started = true;
+        enc.c = packed(false, 0xFF);
+        enc.byte_out().unwrap();
+        assert!(enc.w.is_empty());
+
+        enc.c = packed(false, 0x10);
+        enc.byte_out().unwrap();
+
+        assert_eq!(vec![0x01, 0xFF, 0x00], enc.w);
+        assert_eq!(0x10, enc.cache);
+    }
+
+    #[test]
+    fn mps_fast_path_still_adds_qe_to_c() {
+        // index 0's qe is 0x5A1D; starting from a=0xFFFF leaves a residual
+        // a - qe = 0xA5E2 with bit 15 set, so this takes the no-exchange,
+        // no-renormalization fast path. `c` must still move past the LPS
+        // sub-interval by `qe`, even though `a` and the state index don't
+        // change.
+        let mut enc = ArithEncoder::new(Vec::new());
+        enc.a = 0xFFFF;
+        enc.c = 0x100;
+        enc.ct = 5;
+        let mut ctx = ArithContext { index: 0, mps: 0 };
+
+        enc.encode_bit(&mut ctx, 0).unwrap();
+
+        assert_eq!(0xA5E2, enc.a);
+        assert_eq!(0x5B1D, enc.c);
+        assert_eq!(5, enc.ct);
+        assert_eq!(0, ctx.index);
+    }
+
+    #[test]
+    fn mps_slow_path_without_exchange_keeps_the_residual_a() {
+        // index 0's qe is 0x5A1D; starting from a=0xBA1D leaves a residual
+        // a - qe = 0x6000, which is >= qe but < 0x8000: no conditional
+        // exchange, so `a` keeps its residual width instead of being reset
+        // to `qe`, while `c` still advances by `qe` before renormalizing.
+        let mut enc = ArithEncoder::new(Vec::new());
+        enc.a = 0xBA1D;
+        enc.c = 0x1000;
+        enc.ct = 5;
+        let mut ctx = ArithContext { index: 0, mps: 0 };
+
+        enc.encode_bit(&mut ctx, 0).unwrap();
+
+        assert_eq!(0xC000, enc.a);
+        assert_eq!(0xD43A, enc.c);
+        assert_eq!(4, enc.ct);
+        assert_eq!(1, ctx.index);
+    }
+
+    #[test]
+    fn encoding_never_emits_an_unstuffed_0xff() {
+        let mut enc = ArithEncoder::new(Vec::new());
+        let mut ctx = ArithContext::default();
+
+        // Encode a long run of mostly-MPS decisions with an LPS every
+        // seventh one: the LPS decisions keep renormalizing (and thus
+        // emitting bytes), exercising many byte_out calls (and therefore
+        // carries and 0xFF runs) in a small test.
+        for i in 0..2000u32 {
+            enc.encode_bit(&mut ctx, (i % 7 == 0) as u8).unwrap();
+        }
+        enc.flush().unwrap();
+
+        let mut i = 0;
+        while i < enc.w.len() {
+            if enc.w[i] == 0xFF {
+                assert_eq!(
+                    Some(&0x00),
+                    enc.w.get(i + 1),
+                    "unstuffed 0xFF at offset {}",
+                    i
+                );
+                i += 2;
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
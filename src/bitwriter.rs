@@ -0,0 +1,115 @@
+//! MSB-first bit packing for entropy-coded scan data.
+//!
+//! JPEG's entropy-coded segments (baseline or progressive, DC or AC) are
+//! all, underneath the Huffman/arithmetic symbol choice, a stream of
+//! variable-length bit groups packed most-significant-bit first into
+//! bytes, with a `0x00` stuffed after any output `0xFF` byte so the
+//! bitstream can't be confused with a marker (ITU-T T.81 F.1.2.3). This is
+//! the packer `progressive`'s scan encoders build on.
+
+use std::io::{Result as IOResult, Write};
+
+pub(crate) struct BitWriter<W: Write> {
+    w: W,
+    acc: u32,
+    nbits: u8,
+}
+
+impl<W: Write> BitWriter<W> {
+    pub fn new(w: W) -> Self {
+        BitWriter { w, acc: 0, nbits: 0 }
+    }
+
+    /// Append the low `len` bits of `bits`, most-significant first.
+    /// `len` must be `0..=24`; a code word (at most 16 bits) and its
+    /// magnitude bits are appended with separate calls.
+    pub fn put_bits(&mut self, bits: u32, len: u8) -> IOResult<()> {
+        debug_assert!(len <= 24, "BitWriter::put_bits: len {} out of range", len);
+        if len == 0 {
+            return Ok(());
+        }
+
+        self.acc = (self.acc << len) | (bits & ((1u32 << len) - 1));
+        self.nbits += len;
+
+        while self.nbits >= 8 {
+            self.nbits -= 8;
+            let byte = ((self.acc >> self.nbits) & 0xFF) as u8;
+            self.w.write_all(&[byte])?;
+            if byte == 0xFF {
+                self.w.write_all(&[0x00])?;
+            }
+            // Drop the bits just emitted so they can't resurface (shifted
+            // further up) the next time this loop reads `acc`.
+            self.acc &= (1u32 << self.nbits) - 1;
+        }
+
+        Ok(())
+    }
+
+    /// Pad any partial final byte with 1 bits, the standard JPEG
+    /// bit-stuffing convention for entropy-coded segments, and flush it.
+    /// Call this once at the end of a scan, before the next marker.
+    pub fn flush(&mut self) -> IOResult<()> {
+        if self.nbits > 0 {
+            let pad = 8 - self.nbits;
+            self.put_bits((1u32 << pad) - 1, pad)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_bits_most_significant_bit_first() {
+        let mut w = BitWriter::new(Vec::new());
+        w.put_bits(0b1010, 4).unwrap();
+        w.put_bits(0b0110, 4).unwrap();
+        w.flush().unwrap();
+
+        assert_eq!(vec![0b1010_0110], w.w);
+    }
+
+    #[test]
+    fn splits_bit_groups_across_byte_boundaries() {
+        let mut w = BitWriter::new(Vec::new());
+        w.put_bits(0b101, 3).unwrap();
+        w.put_bits(0b11111111, 8).unwrap();
+        w.put_bits(0b01, 2).unwrap();
+        w.flush().unwrap();
+
+        // 101 11111111 01 -> 1011111111101, padded with 1s to 16 bits:
+        // 10111111 11101111.
+        assert_eq!(vec![0b1011_1111, 0b1110_1111], w.w);
+    }
+
+    #[test]
+    fn stuffs_a_zero_byte_after_an_output_0xff() {
+        let mut w = BitWriter::new(Vec::new());
+        w.put_bits(0xFF, 8).unwrap();
+        w.flush().unwrap();
+
+        assert_eq!(vec![0xFF, 0x00], w.w);
+    }
+
+    #[test]
+    fn flush_pads_with_one_bits() {
+        let mut w = BitWriter::new(Vec::new());
+        w.put_bits(0b101, 3).unwrap();
+        w.flush().unwrap();
+
+        assert_eq!(vec![0b1011_1111], w.w);
+    }
+
+    #[test]
+    fn flush_on_a_byte_aligned_stream_writes_nothing_extra() {
+        let mut w = BitWriter::new(Vec::new());
+        w.put_bits(0xAB, 8).unwrap();
+        w.flush().unwrap();
+
+        assert_eq!(vec![0xAB], w.w);
+    }
+}
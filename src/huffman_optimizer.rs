@@ -0,0 +1,233 @@
+//! JPEG Annex K.2 two-pass optimal Huffman table construction.
+//!
+//! Builds length-limited canonical Huffman tables tailored to the actual
+//! symbol-frequency histogram of an image, the way libjpeg does when
+//! `optimize_coding` is set: a first encode pass accumulates histograms
+//! instead of emitting bits, this module turns each histogram into a
+//! `BITS`/values pair via the Annex K.2 procedure, and a second pass emits
+//! the entropy-coded data using the resulting table.
+
+/// Build a length-limited canonical Huffman table from a symbol-frequency
+/// histogram, following JPEG Annex K.2 (`jpeg_gen_optimal_table` in
+/// libjpeg).
+///
+/// `freq` must have 257 entries: 256 real symbols (DC/AC categories, or
+/// run/category byte for AC) plus one reserved slot used internally to
+/// guarantee that no code word ends up all ones. Returns the 16-entry
+/// `BITS` array (number of codes of each length, 1-indexed by
+/// `BITS[len - 1]`) and the canonical value list, both in the shape
+/// [`crate::writer::JfifWriter::write_huffman_segment`] already consumes.
+pub(crate) fn build_optimal_table(freq: &[u32; 257]) -> ([u8; 16], Vec<u8>) {
+    // A component that never occurs (e.g. the chroma tables when encoding
+    // a grayscale image) has an all-zero histogram; there is no table to
+    // build, so return one up front rather than let every length bucket
+    // below stay empty and underflow the "find the highest used length"
+    // search.
+    if freq[..256].iter().all(|&f| f == 0) {
+        return ([0u8; 16], Vec::new());
+    }
+
+    let mut freq = *freq;
+    freq[256] = 1;
+
+    let mut codesize = [0u32; 257];
+    let mut others: [i32; 257] = [-1; 257];
+
+    loop {
+        // Find the two smallest nonzero frequencies still in play.
+        let mut v1: i32 = -1;
+        let mut least = u32::MAX;
+        for (i, &f) in freq.iter().enumerate() {
+            if f != 0 && f <= least {
+                least = f;
+                v1 = i as i32;
+            }
+        }
+
+        let mut v2: i32 = -1;
+        let mut least2 = u32::MAX;
+        for (i, &f) in freq.iter().enumerate() {
+            if f != 0 && i as i32 != v1 && f <= least2 {
+                least2 = f;
+                v2 = i as i32;
+            }
+        }
+
+        if v2 < 0 {
+            break;
+        }
+
+        freq[v1 as usize] += freq[v2 as usize];
+        freq[v2 as usize] = 0;
+
+        codesize[v1 as usize] += 1;
+        while others[v1 as usize] != -1 {
+            v1 = others[v1 as usize];
+            codesize[v1 as usize] += 1;
+        }
+        others[v1 as usize] = v2;
+
+        codesize[v2 as usize] += 1;
+        while others[v2 as usize] != -1 {
+            v2 = others[v2 as usize];
+            codesize[v2 as usize] += 1;
+        }
+    }
+
+    // Count how many symbols ended up at each code length. A symbol's
+    // codesize is incremented once per merge its chain takes part in, and
+    // the merge loop above runs at most 256 times (257 symbols, including
+    // the reserved slot, merge down to 1), so codesize can't exceed 256
+    // regardless of how skewed `freq` is; size `bits` to that true bound
+    // rather than the 16-bit length limit applied below.
+    let mut bits = [0i32; 257];
+    for &size in &codesize {
+        if size > 0 {
+            bits[size as usize] += 1;
+        }
+    }
+
+    // Limit code lengths to 16 bits (Annex K.2's length-limiting loop):
+    // move a pair of the longest codes up a level by sacrificing one
+    // shorter code, repeating until nothing exceeds 16 bits.
+    for i in (17..=256).rev() {
+        while bits[i] > 0 {
+            let mut j = i - 2;
+            while bits[j] == 0 {
+                j -= 1;
+            }
+            bits[i] -= 2;
+            bits[i - 1] += 1;
+            bits[j + 1] += 2;
+            bits[j] -= 1;
+        }
+    }
+
+    // Drop the reserved all-ones code word from whichever length it landed
+    // at so it's never actually assigned to a real symbol.
+    let mut i = 16;
+    while bits[i] == 0 {
+        i -= 1;
+    }
+    bits[i] -= 1;
+
+    let mut out_bits = [0u8; 16];
+    for (len, &count) in bits[1..=16].iter().enumerate() {
+        out_bits[len] = count as u8;
+    }
+
+    // The length-limiting loop above only redistributes how many codes
+    // exist at each length, not which symbols ended up more or less
+    // deeply nested by the merge order; a symbol's pre-limit `codesize`
+    // can therefore be longer than 16 even though it's assigned a
+    // length-limited code in the end. So build the canonical symbol order
+    // by sorting everything that took part in a merge (including the
+    // reserved slot) by its pre-limit code length, drop the reserved
+    // slot, and read final lengths off by slicing that order with the
+    // (already length-limited) `out_bits` counts, rather than re-checking
+    // each symbol's own `codesize` against a length.
+    let mut order: Vec<u32> = (0..257u32).filter(|&s| codesize[s as usize] > 0).collect();
+    order.sort_by_key(|&s| codesize[s as usize]);
+    order.retain(|&s| s != 256);
+
+    let mut values = Vec::with_capacity(order.len());
+    let mut order = order.into_iter();
+    for &count in &out_bits {
+        for _ in 0..count {
+            if let Some(symbol) = order.next() {
+                values.push(symbol as u8);
+            }
+        }
+    }
+
+    (out_bits, values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_histogram_does_not_panic() {
+        let freq = [0u32; 257];
+        let (bits, values) = build_optimal_table(&freq);
+
+        assert_eq!([0u8; 16], bits);
+        assert!(values.is_empty());
+    }
+
+    #[test]
+    fn single_symbol_gets_a_one_bit_code() {
+        let mut freq = [0u32; 257];
+        freq[42] = 100;
+
+        let (bits, values) = build_optimal_table(&freq);
+
+        assert_eq!(1, bits[0]);
+        assert_eq!(vec![42u8], values);
+    }
+
+    #[test]
+    fn shorter_codes_go_to_more_frequent_symbols() {
+        // Classic textbook histogram: frequencies 1, 1, 2, 4, 8 produce
+        // codeword lengths 4, 5, 3, 2, 1 respectively (the most frequent
+        // symbol gets the shortest code; the reserved slot takes the other
+        // 5-bit place, leaving one code at each length 1..=5).
+        let mut freq = [0u32; 257];
+        freq[0] = 1;
+        freq[1] = 1;
+        freq[2] = 2;
+        freq[3] = 4;
+        freq[4] = 8;
+
+        let (bits, values) = build_optimal_table(&freq);
+
+        assert_eq!(5, values.len());
+        // Canonical order is ascending by length, so the most frequent
+        // symbol (4) must be the sole 1-bit code and come first.
+        assert_eq!(4u8, values[0]);
+        assert_eq!([1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], bits);
+    }
+
+    #[test]
+    fn every_code_length_is_at_most_16_bits() {
+        // A Fibonacci-like histogram is the classic way to force maximally
+        // unbalanced (and thus maximally deep) Huffman trees.
+        let mut freq = [0u32; 257];
+        let (mut a, mut b) = (1u32, 1u32);
+        for f in freq.iter_mut().take(40) {
+            *f = a;
+            let next = a.saturating_add(b);
+            a = b;
+            b = next;
+        }
+
+        let (bits, values) = build_optimal_table(&freq);
+
+        assert_eq!(40, values.len());
+        assert_eq!(40u32, bits.iter().map(|&c| c as u32).sum());
+    }
+
+    #[test]
+    fn a_deeply_skewed_histogram_does_not_panic() {
+        // Seeding the Fibonacci ratio at 1, 2 (rather than 1, 1) keeps the
+        // running merged node just below each next frequency, letting the
+        // merge loop chain every symbol into one maximally unbalanced tree:
+        // the rarest symbol lands at a pre-limiting codesize of 40, which used to
+        // index clean off the end of a fixed `[i32; 33]` bucket array,
+        // while every frequency still comfortably fits in a u32.
+        let mut freq = [0u32; 257];
+        let (mut a, mut b) = (1u32, 2u32);
+        for f in freq.iter_mut().take(40) {
+            *f = a;
+            let next = a + b;
+            a = b;
+            b = next;
+        }
+
+        let (bits, values) = build_optimal_table(&freq);
+
+        assert_eq!(40, values.len());
+        assert_eq!(40u32, bits.iter().map(|&c| c as u32).sum());
+    }
+}
@@ -0,0 +1,61 @@
+//! JPEG marker codes (ITU-T T.81 Table B.1), the byte following the
+//! 0xFF prefix every marker starts with.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Marker {
+    /// Start of frame, baseline DCT (Huffman).
+    SOF0,
+    /// Start of frame, extended sequential DCT (Huffman).
+    SOF1,
+    /// Start of frame, progressive DCT (Huffman).
+    SOF2,
+    /// Start of frame, extended sequential DCT (arithmetic).
+    SOF9,
+    /// Start of frame, progressive DCT (arithmetic).
+    SOF10,
+    /// Define Huffman table(s).
+    DHT,
+    /// Define arithmetic coding conditioning(s).
+    DAC,
+    /// Restart with module 8 count `m` (`0..=7`).
+    RST(u8),
+    /// Start of image.
+    SOI,
+    /// End of image.
+    EOI,
+    /// Start of scan.
+    SOS,
+    /// Define quantization table(s).
+    DQT,
+    /// Define restart interval.
+    DRI,
+    /// Application segment `n` (`0..=15`).
+    APP(u8),
+}
+
+impl From<Marker> for u8 {
+    fn from(marker: Marker) -> Self {
+        match marker {
+            Marker::SOF0 => 0xC0,
+            Marker::SOF1 => 0xC1,
+            Marker::SOF2 => 0xC2,
+            Marker::SOF9 => 0xC9,
+            Marker::SOF10 => 0xCA,
+            Marker::DHT => 0xC4,
+            Marker::DAC => 0xCC,
+            Marker::RST(m) => {
+                assert!(m < 8, "Bad restart marker index: {}", m);
+                0xD0 + m
+            }
+            Marker::SOI => 0xD8,
+            Marker::EOI => 0xD9,
+            Marker::SOS => 0xDA,
+            Marker::DQT => 0xDB,
+            Marker::DRI => 0xDD,
+            Marker::APP(n) => {
+                assert!(n < 16, "Bad APP marker index: {}", n);
+                0xE0 + n
+            }
+        }
+    }
+}
@@ -0,0 +1,708 @@
+//! Scan scheduling and entropy coding for progressive JPEG (SOF2) encoding.
+//!
+//! A progressive scan is described by the four scan parameters from the SOS
+//! segment: `Ss`/`Se` select a contiguous band of the zig-zag coefficient
+//! order (spectral selection) and `Ah`/`Al` select a bit-plane refinement
+//! step within that band (successive approximation). [`default_scan_script`]
+//! describes *which* scans to run and in *what* order, mirroring the scan
+//! scripts `jpeg_simple_progression` builds in libjpeg-turbo's `jcparam.c`;
+//! the rest of this module is the bit-level entropy coding ITU-T T.81
+//! Annex G describes for each kind of scan (DC first/refinement, AC
+//! first/refinement), plus [`encode_progressive_frame`], which drives the
+//! whole frame through [`crate::writer::JfifWriter`].
+
+use crate::bitwriter::BitWriter;
+use crate::huffman::HuffmanTable;
+use crate::marker::Marker;
+use crate::writer::JfifWriter;
+use std::io::{Result as IOResult, Write};
+
+/// The four scan parameters carried by a progressive SOS segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ScanSpec {
+    /// Start of spectral selection (0 for a DC scan).
+    pub ss: u8,
+    /// End of spectral selection (0 for a DC scan).
+    pub se: u8,
+    /// Successive approximation high bit position (point transform of the
+    /// previous pass over this band, 0 on the first pass).
+    pub ah: u8,
+    /// Successive approximation low bit position (point transform applied
+    /// by this pass).
+    pub al: u8,
+}
+
+impl ScanSpec {
+    const fn dc(ah: u8, al: u8) -> Self {
+        ScanSpec { ss: 0, se: 0, ah, al }
+    }
+
+    const fn ac(ss: u8, se: u8, ah: u8, al: u8) -> Self {
+        ScanSpec { ss, se, ah, al }
+    }
+
+    /// Whether this scan refines coefficients an earlier scan already sent
+    /// (`Ah > 0`), as opposed to a first pass over the band (`Ah == 0`).
+    pub fn is_refinement(&self) -> bool {
+        self.ah > 0
+    }
+}
+
+/// Build the default progressive scan script: a DC scan, a DC refinement,
+/// then the AC band split into `1..=5` and `6..=63` described in the
+/// request, each band sent as a first pass followed by one refinement
+/// pass. This is a smaller script than libjpeg-turbo's default (which
+/// splits the AC bands further and adds more refinement bit-planes), but
+/// follows the same spectral-selection-then-successive-approximation
+/// structure and is enough to produce a standard-conforming progressive
+/// file.
+///
+/// Every refinement scan in this script refines exactly one bit below its
+/// first pass (`Al` goes `1` then `0`), which [`encode_progressive_frame`]
+/// relies on to derive "was this coefficient already significant"
+/// (`point_transform(coefficient, Al + 1) != 0`) straight from the full-precision
+/// coefficient instead of tracking separate per-block significance state
+/// across scans.
+pub(crate) fn default_scan_script() -> Vec<ScanSpec> {
+    vec![
+        ScanSpec::dc(0, 1),
+        ScanSpec::dc(1, 0),
+        ScanSpec::ac(1, 5, 0, 1),
+        ScanSpec::ac(1, 5, 1, 0),
+        ScanSpec::ac(6, 63, 0, 1),
+        ScanSpec::ac(6, 63, 1, 0),
+    ]
+}
+
+/// Category (magnitude bit length) and value bits for a signed DC
+/// difference or AC coefficient, per ITU-T T.81 Table F.1/F.2: a nonzero
+/// value's bits are its own bits if positive, or the low `category` bits
+/// of `value - 1` if negative (the standard one's-complement-style
+/// encoding that makes the bits for `-v` the bitwise complement of `v`'s).
+pub(crate) fn category_and_bits(value: i32) -> (u8, u16) {
+    if value == 0 {
+        return (0, 0);
+    }
+
+    let magnitude = value.unsigned_abs();
+    let category = (32 - magnitude.leading_zeros()) as u8;
+    let bits = if value > 0 {
+        magnitude
+    } else {
+        let mask = (1u32 << category) - 1;
+        (value - 1) as u32 & mask
+    };
+
+    (category, bits as u16)
+}
+
+/// Point transform by `al` (ITU-T T.81 Annex G): shift a coefficient's
+/// *magnitude* right by `al` bits, rounding toward zero, then reapply its
+/// sign. A raw arithmetic shift rounds negative values toward negative
+/// infinity instead (e.g. `-3 >> 1 == -2` in two's complement), which is
+/// the wrong transform for a signed AC coefficient — the spec's point
+/// transform of `-3` by 1 is `-1`, not `-2`. DC scans shift the DC value
+/// with a plain arithmetic shift instead, which the DC refinement bits
+/// ([`encode_dc_refine`]) build on.
+pub(crate) fn point_transform(coefficient: i32, al: u8) -> i32 {
+    if coefficient < 0 {
+        -(-coefficient >> al)
+    } else {
+        coefficient >> al
+    }
+}
+
+/// Encode one block's DC contribution to a first DC scan (`Ah == 0`): the
+/// (already point-transformed) difference from the previous block's
+/// point-transformed DC, as a Huffman-coded category followed by that
+/// many magnitude bits (ITU-T T.81 F.1.2.1, F.2.4, G.1.2.1).
+/// `code_for_category` looks up the DC table's code word and length for a
+/// category.
+pub(crate) fn encode_dc_first<W: Write>(
+    bits: &mut BitWriter<W>,
+    mut code_for_category: impl FnMut(u8) -> (u16, u8),
+    diff: i32,
+) -> IOResult<()> {
+    let (category, value_bits) = category_and_bits(diff);
+    let (code, code_len) = code_for_category(category);
+    bits.put_bits(code as u32, code_len)?;
+    bits.put_bits(value_bits as u32, category)
+}
+
+/// Encode one block's DC contribution to a DC refinement scan (`Ah > 0`):
+/// a single raw bit, the next bit of the block's (unshifted) DC
+/// coefficient at position `al` (ITU-T T.81 G.1.2.1).
+pub(crate) fn encode_dc_refine<W: Write>(bits: &mut BitWriter<W>, dc: i32, al: u8) -> IOResult<()> {
+    bits.put_bits(((dc >> al) & 1) as u32, 1)
+}
+
+/// Cross-block state for an AC first scan (`Ah == 0`): runs of
+/// all-zero-in-band blocks are coalesced into a single `EOBn` symbol
+/// instead of being spelled out block by block (ITU-T T.81 G.1.2.2), so
+/// the pending run length has to carry across blocks in the same scan.
+#[derive(Debug, Default)]
+pub(crate) struct AcFirstScanState {
+    eob_run: u32,
+}
+
+impl AcFirstScanState {
+    /// Encode one block's contribution. `coefficients` holds the scan's
+    /// band (`ss..=se`) in zig-zag order, at full (unshifted) magnitude.
+    /// `code_for_run_size` and `code_for_eobn` look up the AC table's code
+    /// word and length for a `(zero run, category)` pair (`(15, 0)` is
+    /// ZRL) and for an EOBn run-length class.
+    pub fn encode_block<W: Write>(
+        &mut self,
+        bits: &mut BitWriter<W>,
+        mut code_for_run_size: impl FnMut(u8, u8) -> (u16, u8),
+        mut code_for_eobn: impl FnMut(u8) -> (u16, u8),
+        coefficients: &[i32],
+        al: u8,
+    ) -> IOResult<()> {
+        let last_nonzero = coefficients.iter().rposition(|&c| point_transform(c, al) != 0);
+
+        let Some(last_nonzero) = last_nonzero else {
+            self.eob_run += 1;
+            if self.eob_run == 0x7FFF {
+                self.flush_eob_run(bits, &mut code_for_eobn)?;
+            }
+            return Ok(());
+        };
+
+        self.flush_eob_run(bits, &mut code_for_eobn)?;
+
+        let mut run = 0u8;
+        for &coefficient in &coefficients[..=last_nonzero] {
+            let shifted = point_transform(coefficient, al);
+
+            if shifted == 0 {
+                if run == 15 {
+                    let (code, len) = code_for_run_size(15, 0);
+                    bits.put_bits(code as u32, len)?;
+                    run = 0;
+                } else {
+                    run += 1;
+                }
+                continue;
+            }
+
+            let (category, value_bits) = category_and_bits(shifted);
+            let (code, len) = code_for_run_size(run, category);
+            bits.put_bits(code as u32, len)?;
+            bits.put_bits(value_bits as u32, category)?;
+            run = 0;
+        }
+
+        Ok(())
+    }
+
+    fn flush_eob_run<W: Write>(
+        &mut self,
+        bits: &mut BitWriter<W>,
+        code_for_eobn: &mut impl FnMut(u8) -> (u16, u8),
+    ) -> IOResult<()> {
+        if self.eob_run == 0 {
+            return Ok(());
+        }
+
+        // EOBn's "n" is the run count's bit length minus one; the
+        // remaining low bits of the run count follow as extra bits, the
+        // same category/magnitude-bits shape DC and AC symbols use.
+        let n = (32 - self.eob_run.leading_zeros() - 1) as u8;
+        let (code, len) = code_for_eobn(n);
+        bits.put_bits(code as u32, len)?;
+        if n > 0 {
+            let extra = self.eob_run - (1 << n);
+            bits.put_bits(extra, n)?;
+        }
+        self.eob_run = 0;
+        Ok(())
+    }
+
+    /// Flush any pending EOB run at the end of a scan.
+    pub fn finish<W: Write>(
+        &mut self,
+        bits: &mut BitWriter<W>,
+        mut code_for_eobn: impl FnMut(u8) -> (u16, u8),
+    ) -> IOResult<()> {
+        self.flush_eob_run(bits, &mut code_for_eobn)
+    }
+}
+
+/// Cross-block state for an AC refinement scan (`Ah > 0`): an `EOBn` run
+/// here must still carry forward the correction bits for every
+/// already-significant coefficient in the blocks it spans, so bits
+/// collected while a run is open are buffered in `pending_corrections` and
+/// flush together with the symbol that ends the run (ITU-T T.81 G.1.2.3,
+/// following the same structure as libjpeg-turbo's
+/// `encode_mcu_AC_refine`).
+#[derive(Debug, Default)]
+pub(crate) struct AcRefineScanState {
+    eob_run: u32,
+    pending_corrections: Vec<u8>,
+}
+
+impl AcRefineScanState {
+    /// Encode one block's contribution. `coefficients` holds the scan's
+    /// band in zig-zag order, at full (unshifted) magnitude;
+    /// `previously_significant[i]` reports whether `coefficients[i]` was
+    /// already nonzero after being shifted by the scan's previous pass
+    /// (see [`default_scan_script`]'s note on how that's derived without
+    /// extra cross-scan state).
+    pub fn encode_block<W: Write>(
+        &mut self,
+        bits: &mut BitWriter<W>,
+        mut code_for_run_size: impl FnMut(u8, u8) -> (u16, u8),
+        mut code_for_eobn: impl FnMut(u8) -> (u16, u8),
+        coefficients: &[i32],
+        previously_significant: &[bool],
+        al: u8,
+    ) -> IOResult<()> {
+        debug_assert_eq!(coefficients.len(), previously_significant.len());
+
+        // The block's EOB: the last position with either a prior or a
+        // newly significant coefficient. Positions after it contribute no
+        // symbols at all, not even correction bits.
+        let eob = coefficients
+            .iter()
+            .zip(previously_significant)
+            .rposition(|(&c, &prev)| prev || point_transform(c, al).abs() == 1);
+
+        let Some(eob) = eob else {
+            // Nothing significant anywhere in this block's band: fold
+            // into the pending EOB run. Nothing was ever significant, so
+            // there are no correction bits to carry for this block.
+            self.eob_run += 1;
+            if self.eob_run == 0x7FFF {
+                self.flush_eob_run(bits, &mut code_for_eobn)?;
+            }
+            return Ok(());
+        };
+
+        let mut run = 0u8;
+        let mut symbol_emitted = false;
+        for i in 0..=eob {
+            let shifted = point_transform(coefficients[i], al);
+
+            if previously_significant[i] {
+                self.pending_corrections.push((shifted & 1) as u8);
+                continue;
+            }
+
+            if shifted.abs() == 1 {
+                self.flush_eob_run(bits, &mut code_for_eobn)?;
+                let (code, len) = code_for_run_size(run, 1);
+                bits.put_bits(code as u32, len)?;
+                bits.put_bits((shifted > 0) as u32, 1)?;
+                self.drain_pending_corrections(bits)?;
+                run = 0;
+                symbol_emitted = true;
+            } else {
+                run += 1;
+                if run == 16 {
+                    self.flush_eob_run(bits, &mut code_for_eobn)?;
+                    let (code, len) = code_for_run_size(15, 0);
+                    bits.put_bits(code as u32, len)?;
+                    self.drain_pending_corrections(bits)?;
+                    run = 0;
+                    symbol_emitted = true;
+                }
+            }
+        }
+
+        if !symbol_emitted {
+            // Every significant position in this block's band was a
+            // correction bit with no newly significant coefficient (and
+            // so no run/size symbol) to flush them through; they stay
+            // buffered in `pending_corrections` and this block folds into
+            // the pending EOB run like a fully insignificant one would.
+            self.eob_run += 1;
+            if self.eob_run == 0x7FFF {
+                self.flush_eob_run(bits, &mut code_for_eobn)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn drain_pending_corrections<W: Write>(&mut self, bits: &mut BitWriter<W>) -> IOResult<()> {
+        for bit in self.pending_corrections.drain(..) {
+            bits.put_bits(bit as u32, 1)?;
+        }
+        Ok(())
+    }
+
+    fn flush_eob_run<W: Write>(
+        &mut self,
+        bits: &mut BitWriter<W>,
+        code_for_eobn: &mut impl FnMut(u8) -> (u16, u8),
+    ) -> IOResult<()> {
+        if self.eob_run == 0 {
+            return Ok(());
+        }
+
+        let n = (32 - self.eob_run.leading_zeros() - 1) as u8;
+        let (code, len) = code_for_eobn(n);
+        bits.put_bits(code as u32, len)?;
+        if n > 0 {
+            let extra = self.eob_run - (1 << n);
+            bits.put_bits(extra, n)?;
+        }
+        self.eob_run = 0;
+        self.drain_pending_corrections(bits)
+    }
+
+    /// Flush any pending EOB run (and the correction bits riding with it)
+    /// at the end of a scan.
+    pub fn finish<W: Write>(
+        &mut self,
+        bits: &mut BitWriter<W>,
+        mut code_for_eobn: impl FnMut(u8) -> (u16, u8),
+    ) -> IOResult<()> {
+        self.flush_eob_run(bits, &mut code_for_eobn)
+    }
+}
+
+/// One component's contribution to a progressive frame: its id as it
+/// appears in the frame header, which Huffman table slots it draws DC/AC
+/// statistics from, and its coefficient blocks (64 entries each, in
+/// zig-zag order, at full unquantized-category magnitude).
+pub(crate) struct ScanComponent<'a> {
+    pub id: u8,
+    pub dc_table: u8,
+    pub ac_table: u8,
+    pub blocks: &'a [[i32; 64]],
+}
+
+/// Encode a full progressive frame: the SOF2 frame header, followed by
+/// every scan [`default_scan_script`] describes as its own SOS segment.
+///
+/// Every scan here is single-component and non-interleaved: ITU-T T.81
+/// allows, but doesn't require, MCU-interleaved multi-component scans, and
+/// a separate scan per component is the simplest spec-conforming way to
+/// lay one out. libjpeg-turbo's default progressive script interleaves
+/// the DC scans for density, which this doesn't.
+pub(crate) fn encode_progressive_frame<W: Write>(
+    writer: &mut JfifWriter<W>,
+    width: u16,
+    height: u16,
+    frame_components: &[(u8, u8, u8, u8)],
+    components: &[ScanComponent],
+    dc_tables: &[&HuffmanTable],
+    ac_tables: &[&HuffmanTable],
+) -> IOResult<()> {
+    writer.write_frame_header(Marker::SOF2, width, height, frame_components)?;
+
+    for scan in default_scan_script() {
+        for component in components {
+            if scan.se == 0 {
+                encode_dc_scan(writer, &scan, component, dc_tables)?;
+            } else {
+                encode_ac_scan(writer, &scan, component, ac_tables)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn encode_dc_scan<W: Write>(
+    writer: &mut JfifWriter<W>,
+    scan: &ScanSpec,
+    component: &ScanComponent,
+    dc_tables: &[&HuffmanTable],
+) -> IOResult<()> {
+    writer.write_scan_header(&[(component.id, component.dc_table, 0)], scan.ss, scan.se, scan.ah, scan.al)?;
+
+    let mut buf = Vec::new();
+    {
+        let mut bits = BitWriter::new(&mut buf);
+        let table = dc_tables[component.dc_table as usize];
+        // Holds the *point-transformed* (shifted) previous DC, not the raw
+        // value: the spec's difference is `(dc >> al) - (predictor >> al)`,
+        // not `(dc - predictor) >> al` — those diverge whenever the two raw
+        // DC values straddle an odd/even boundary under the shift.
+        let mut predictor = 0i32;
+
+        for block in component.blocks {
+            let dc = block[0];
+            if scan.is_refinement() {
+                encode_dc_refine(&mut bits, dc, scan.al)?;
+            } else {
+                let shifted_dc = dc >> scan.al;
+                let diff = shifted_dc - predictor;
+                encode_dc_first(&mut bits, |category| table.code(category), diff)?;
+                predictor = shifted_dc;
+            }
+        }
+
+        bits.flush()?;
+    }
+    writer.write(&buf)
+}
+
+fn encode_ac_scan<W: Write>(
+    writer: &mut JfifWriter<W>,
+    scan: &ScanSpec,
+    component: &ScanComponent,
+    ac_tables: &[&HuffmanTable],
+) -> IOResult<()> {
+    writer.write_scan_header(&[(component.id, 0, component.ac_table)], scan.ss, scan.se, scan.ah, scan.al)?;
+
+    let band = scan.ss as usize..=scan.se as usize;
+    let mut buf = Vec::new();
+    {
+        let mut bits = BitWriter::new(&mut buf);
+        let table = ac_tables[component.ac_table as usize];
+
+        if scan.is_refinement() {
+            let mut state = AcRefineScanState::default();
+            for block in component.blocks {
+                let coefficients = &block[band.clone()];
+                let previously_significant: Vec<bool> = coefficients
+                    .iter()
+                    .map(|&c| point_transform(c, scan.al + 1) != 0)
+                    .collect();
+                state.encode_block(
+                    &mut bits,
+                    |run, category| table.code_for_run(run, category),
+                    |n| table.code_for_eobn(n),
+                    coefficients,
+                    &previously_significant,
+                    scan.al,
+                )?;
+            }
+            state.finish(&mut bits, |n| table.code_for_eobn(n))?;
+        } else {
+            let mut state = AcFirstScanState::default();
+            for block in component.blocks {
+                let coefficients = &block[band.clone()];
+                state.encode_block(
+                    &mut bits,
+                    |run, category| table.code_for_run(run, category),
+                    |n| table.code_for_eobn(n),
+                    coefficients,
+                    scan.al,
+                )?;
+            }
+            state.finish(&mut bits, |n| table.code_for_eobn(n))?;
+        }
+
+        bits.flush()?;
+    }
+    writer.write(&buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stand-in code tables for tests: every category/EOBn class maps to a
+    /// fixed-width code equal to its own class number, and a (run, category)
+    /// pair maps to its category's code (ZRL to 15), so encoded output is
+    /// easy to predict without depending on `crate::huffman`.
+    fn identity_code(class: u8) -> (u16, u8) {
+        (class as u16, 8)
+    }
+
+    fn identity_run_code(run: u8, category: u8) -> (u16, u8) {
+        identity_code(if run == 15 && category == 0 { 15 } else { category })
+    }
+
+    #[test]
+    fn category_and_bits_of_zero_is_the_zero_category() {
+        assert_eq!((0, 0), category_and_bits(0));
+    }
+
+    #[test]
+    fn category_and_bits_match_the_standard_table() {
+        // ITU-T T.81 Table F.1: category 3 covers -7..=-4 and 4..=7.
+        assert_eq!((3, 0b000), category_and_bits(-7));
+        assert_eq!((3, 0b011), category_and_bits(-4));
+        assert_eq!((3, 0b100), category_and_bits(4));
+        assert_eq!((3, 0b111), category_and_bits(7));
+    }
+
+    #[test]
+    fn dc_first_scan_emits_category_then_value_bits() {
+        let mut buf = Vec::new();
+        {
+            let mut bits = BitWriter::new(&mut buf);
+            encode_dc_first(&mut bits, identity_code, 5).unwrap();
+            bits.flush().unwrap();
+        }
+        // category(5) == 3, code is the fixed-width (3, 8) stand-in,
+        // followed by the 3 value bits for +5 (0b101).
+        assert_eq!(0b0000_0011, buf[0]);
+        assert_eq!(0b1010_0000 | 0b0001_1111, buf[1] | 0b0001_1111);
+        assert_eq!(2, buf.len());
+    }
+
+    #[test]
+    fn point_transform_rounds_the_magnitude_toward_zero() {
+        // A raw arithmetic right shift would give -2 and -1 here (rounding
+        // toward negative infinity); the spec's point transform rounds the
+        // magnitude toward zero instead.
+        assert_eq!(-1, point_transform(-3, 1));
+        assert_eq!(0, point_transform(-1, 1));
+        assert_eq!(1, point_transform(3, 1));
+        assert_eq!(0, point_transform(1, 1));
+    }
+
+    #[test]
+    fn dc_scan_diffs_shifted_dc_values_not_the_shifted_raw_difference() {
+        // dc=2, predictor=3, al=1: shifting each value first gives 1 and 1,
+        // a diff of 0. Shifting the raw difference instead, (2 - 3) >> 1 ==
+        // -1 >> 1 == -1, would be wrong — exactly the bug this pins down.
+        let shifted_dc = point_transform(2, 1);
+        let shifted_predictor = point_transform(3, 1);
+        let diff = shifted_dc - shifted_predictor;
+        assert_eq!(0, diff);
+
+        let mut buf = Vec::new();
+        {
+            let mut bits = BitWriter::new(&mut buf);
+            encode_dc_first(&mut bits, identity_code, diff).unwrap();
+            bits.flush().unwrap();
+        }
+        // category(0) == 0, so only the stand-in code byte for category 0
+        // is emitted, with no trailing value bits.
+        assert_eq!(vec![0b0000_0000], buf);
+    }
+
+    #[test]
+    fn ac_first_scan_point_transform_can_sink_a_negative_coefficient_to_zero() {
+        let mut buf = Vec::new();
+        {
+            let mut bits = BitWriter::new(&mut buf);
+            let mut state = AcFirstScanState::default();
+            // -1 point-transformed by al=1 rounds to 0 (a raw arithmetic
+            // shift would wrongly give -1), so this block has nothing
+            // significant in-band and should fold into an EOB run.
+            let block = [-1i32];
+            state.encode_block(&mut bits, identity_run_code, identity_code, &block, 1).unwrap();
+            state.finish(&mut bits, identity_code).unwrap();
+        }
+        assert_eq!(vec![0b0000_0000], buf);
+    }
+
+    #[test]
+    fn ac_first_scan_point_transform_rounds_negative_magnitude_toward_zero() {
+        let mut buf = Vec::new();
+        {
+            let mut bits = BitWriter::new(&mut buf);
+            let mut state = AcFirstScanState::default();
+            // -3 point-transformed by al=1 is -1 (category 1); a raw
+            // arithmetic shift would wrongly give -2 (category 2).
+            let block = [-3i32];
+            state.encode_block(&mut bits, identity_run_code, identity_code, &block, 1).unwrap();
+            state.finish(&mut bits, identity_code).unwrap();
+        }
+        assert_eq!(0b0000_0001, buf[0]);
+    }
+
+    #[test]
+    fn dc_refine_scan_emits_a_single_bit() {
+        let mut buf = Vec::new();
+        {
+            let mut bits = BitWriter::new(&mut buf);
+            encode_dc_refine(&mut bits, 0b110, 1).unwrap();
+            encode_dc_refine(&mut bits, 0b110, 1).unwrap();
+            bits.flush().unwrap();
+        }
+        // Both calls read bit 1 of 0b110, which is 1; the resulting byte
+        // (0xFF) gets the standard 0x00 stuffed after it.
+        assert_eq!(vec![0xFF, 0x00], buf);
+    }
+
+    #[test]
+    fn ac_first_scan_runs_of_zero_blocks_coalesce_into_an_eob_run() {
+        let mut buf = Vec::new();
+        let mut call_count = 0usize;
+        {
+            let mut bits = BitWriter::new(&mut buf);
+            let mut state = AcFirstScanState::default();
+            let zero_block = [0i32; 10];
+            for _ in 0..3 {
+                state
+                    .encode_block(&mut bits, identity_run_code, |n| {
+                        call_count += 1;
+                        identity_code(n)
+                    }, &zero_block, 0)
+                    .unwrap();
+            }
+            state
+                .finish(&mut bits, |n| {
+                    call_count += 1;
+                    identity_code(n)
+                })
+                .unwrap();
+        }
+        // Three all-zero blocks fold into one EOBn symbol at the very end,
+        // not one EOB0 symbol per block.
+        assert_eq!(1, call_count);
+    }
+
+    #[test]
+    fn ac_first_scan_encodes_run_then_category_then_value_bits() {
+        let mut buf = Vec::new();
+        {
+            let mut bits = BitWriter::new(&mut buf);
+            let mut state = AcFirstScanState::default();
+            // Two leading zeros, then a coefficient of 3 (category 2).
+            let block = [0, 0, 3, 0, 0, 0, 0, 0, 0, 0];
+            state
+                .encode_block(&mut bits, identity_run_code, identity_code, &block, 0)
+                .unwrap();
+            state.finish(&mut bits, identity_code).unwrap();
+        }
+        // run=2, category=2 -> stand-in code byte 2, then 2 value bits for
+        // +3 (0b11).
+        assert_eq!(0b0000_0010, buf[0]);
+    }
+
+    #[test]
+    fn ac_refine_scan_emits_correction_bits_for_already_significant_coefficients() {
+        let mut buf = Vec::new();
+        {
+            let mut bits = BitWriter::new(&mut buf);
+            let mut state = AcRefineScanState::default();
+            // One already-significant coefficient (value 3, refining bit
+            // 0 -> correction bit 1) followed by nothing else in the band.
+            let block = [3i32];
+            let previously_significant = [true];
+            state
+                .encode_block(&mut bits, identity_run_code, identity_code, &block, &previously_significant, 0)
+                .unwrap();
+            // No newly significant coefficient followed, so the block
+            // folds into a length-1 EOB run; finishing the scan flushes
+            // EOB0 (the stand-in code byte 0) and then the one buffered
+            // correction bit.
+            state.finish(&mut bits, identity_code).unwrap();
+            bits.flush().unwrap();
+        }
+        // The second byte comes out as 0xFF, so it carries the standard
+        // stuffed 0x00 after it.
+        assert_eq!(vec![0b0000_0000, 0xFF, 0x00], buf);
+    }
+
+    #[test]
+    fn ac_refine_scan_emits_sign_bit_for_newly_significant_coefficients() {
+        let mut buf = Vec::new();
+        {
+            let mut bits = BitWriter::new(&mut buf);
+            let mut state = AcRefineScanState::default();
+            let block = [-1i32];
+            let previously_significant = [false];
+            state
+                .encode_block(&mut bits, identity_run_code, identity_code, &block, &previously_significant, 0)
+                .unwrap();
+            state.finish(&mut bits, identity_code).unwrap();
+            bits.flush().unwrap();
+        }
+        // run=0, category=1 -> stand-in code byte 1, then sign bit 0 for
+        // the negative newly-significant coefficient.
+        assert_eq!(0b0000_0001, buf[0]);
+        assert_eq!(0, (buf[1] >> 7) & 1);
+    }
+}
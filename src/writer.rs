@@ -1,6 +1,6 @@
 use crate::marker::Marker;
 
-use std::io::{Write, Result as IOResult};
+use std::io::{Write, Result as IOResult, Error as IOError, ErrorKind};
 use crate::huffman::{HuffmanTable, CodingClass};
 use crate::quantization::QuantizationTable;
 
@@ -25,6 +25,24 @@ pub static ZIGZAG: [u8; 64] = [
     53, 60, 61, 54, 47, 55, 62, 63,
 ];
 
+/// Tag that prefixes every ICC profile APP2 segment, as defined by the ICC
+/// spec's "Embedding ICC Profiles in JFIF Files" note.
+const ICC_PROFILE_TAG: &[u8; 12] = b"ICC_PROFILE\0";
+
+/// Maximum number of profile bytes carried by a single ICC APP2 segment.
+///
+/// This leaves room, within the 65535 byte segment size limit, for the
+/// 12 byte [`ICC_PROFILE_TAG`] and the 2 byte sequence number/chunk count
+/// pair. Matches the chunk size used by libjpeg-turbo's `jcicc.c`.
+const ICC_MARKER_SIZE: usize = 65519;
+
+/// Tag that prefixes the Exif APP1 segment payload.
+const EXIF_TAG: &[u8; 6] = b"Exif\0\0";
+
+/// Maximum number of bytes a single APP1 segment can carry, including the
+/// [`EXIF_TAG`].
+const APP1_MAX_PAYLOAD: usize = 65533;
+
 pub(crate) struct JfifWriter<W: Write> {
     w: W,
 }
@@ -139,5 +157,324 @@ impl<W: Write> JfifWriter<W> {
 
         Ok(())
     }
+
+    /// Append a frame header (SOF0 for baseline, SOF2 for progressive).
+    ///
+    /// `components` holds, per component, `(id, horizontal_sampling,
+    /// vertical_sampling, quantization_table)`.
+    ///
+    /// Layout:
+    /// ```txt
+    /// |--------|---------------|-----------|--------|-------|------------|-----------------------|
+    /// | marker | 16 bit length | precision | height | width | num comps | id/H/V/Tq per comp... |
+    /// |--------|---------------|-----------|--------|-------|------------|-----------------------|
+    /// ```
+    ///
+    pub fn write_frame_header(&mut self, marker: Marker, width: u16, height: u16, components: &[(u8, u8, u8, u8)]) -> IOResult<()> {
+        self.write_marker(marker)?;
+        self.write_u16(2 + 1 + 2 + 2 + 1 + 3 * components.len() as u16)?;
+
+        self.write_u8(8)?;
+        self.write_u16(height)?;
+        self.write_u16(width)?;
+        self.write_u8(components.len() as u8)?;
+
+        for &(id, h, v, tq) in components {
+            self.write_u8(id)?;
+            self.write_u8((h << 4) | v)?;
+            self.write_u8(tq)?;
+        }
+
+        Ok(())
+    }
+
+    /// Append a scan header (SOS), including the progressive scan
+    /// parameters `Ss`, `Se`, `Ah` and `Al`.
+    ///
+    /// `components` holds, per scan component, `(id, dc_table, ac_table)`.
+    /// A baseline scan always covers every component with `Ss = 0`,
+    /// `Se = 63`, `Ah = 0`, `Al = 0`; a progressive scan covers the
+    /// component(s) and spectral band described by a
+    /// [`crate::progressive::ScanSpec`].
+    ///
+    /// Layout:
+    /// ```txt
+    /// |--------|---------------|-----------|----------------------|----|----|-------|
+    /// | 0xFFDA | 16 bit length | num comps | cs/Td/Ta per comp... | Ss | Se | Ah/Al |
+    /// |--------|---------------|-----------|----------------------|----|----|-------|
+    /// ```
+    ///
+    pub fn write_scan_header(&mut self, components: &[(u8, u8, u8)], ss: u8, se: u8, ah: u8, al: u8) -> IOResult<()> {
+        self.write_marker(Marker::SOS)?;
+        self.write_u16(2 + 1 + 2 * components.len() as u16 + 3)?;
+
+        self.write_u8(components.len() as u8)?;
+        for &(id, td, ta) in components {
+            self.write_u8(id)?;
+            self.write_u8((td << 4) | ta)?;
+        }
+
+        self.write_u8(ss)?;
+        self.write_u8(se)?;
+        self.write_u8((ah << 4) | al)?;
+
+        Ok(())
+    }
+
+    /// Append a DRI (Define Restart Interval) segment.
+    ///
+    /// `interval` is the number of MCUs between restart markers; the
+    /// encoder must byte-align the entropy-coded bitstream and reset its DC
+    /// predictors to zero at each boundary, and emit a cycling `RSTm`
+    /// marker (`m` running `0..=7`) via [`write_restart_marker`] there.
+    ///
+    /// Layout:
+    /// ```txt
+    /// |--------|---------------|-----------------|
+    /// | 0xFFDD | 16 bit length | 16 bit interval |
+    /// |--------|---------------|-----------------|
+    /// ```
+    ///
+    /// [`write_restart_marker`]: JfifWriter::write_restart_marker
+    pub fn write_restart_interval(&mut self, interval: u16) -> IOResult<()> {
+        self.write_marker(Marker::DRI)?;
+        self.write_u16(4)?;
+        self.write_u16(interval)?;
+
+        Ok(())
+    }
+
+    /// Append an `RSTm` restart marker, where `m` cycles `0..=7` across
+    /// successive restarts within a scan.
+    pub fn write_restart_marker(&mut self, m: u8) -> IOResult<()> {
+        self.write_marker(Marker::RST(m % 8))
+    }
+
+    /// Append a DAC (Define Arithmetic Conditioning) segment.
+    ///
+    /// Replaces the DHT segments when the scan uses the arithmetic entropy
+    /// coder instead of Huffman coding. `entries` holds, per conditioning
+    /// table, `(class, destination, value)` where `class` is 0 for DC or 1
+    /// for AC, `destination` is 0 for luma or 1 for chroma, and `value`
+    /// packs the conditioning bounds (`L` in the low nibble, `U` in the
+    /// high nibble) for DC or the `Kx` bound for AC.
+    ///
+    /// Layout:
+    /// ```txt
+    /// |--------|---------------|--------------------------|-------|-----|
+    /// | 0xFFCC | 16 bit length | 4 bit class / 4 bit dest | value | ... |
+    /// |--------|---------------|--------------------------|-------|-----|
+    /// ```
+    ///
+    pub fn write_arith_conditioning_segment(&mut self, entries: &[(u8, u8, u8)]) -> IOResult<()> {
+        self.write_marker(Marker::DAC)?;
+        self.write_u16(2 + 2 * entries.len() as u16)?;
+
+        for &(class, destination, value) in entries {
+            assert!(destination < 4, "Bad destination: {}", destination);
+            self.write_u8((class << 4) | destination)?;
+            self.write_u8(value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Append an Exif APP1 segment.
+    ///
+    /// `tiff_payload` is the TIFF (little- or big-endian) header and IFD
+    /// data supplied by the caller, which may include an embedded JPEG
+    /// thumbnail in IFD1. It must be written immediately after APP0 so
+    /// readers that only look at the first segments (cameras, OSes) still
+    /// pick up orientation, DPI and capture metadata.
+    ///
+    /// Since a single APP1 segment is limited to 65533 payload bytes,
+    /// this returns an [`ErrorKind::InvalidInput`] error if `tiff_payload`
+    /// (plus the 6 byte `"Exif\0\0"` tag) would overflow it, rather than
+    /// silently truncating the metadata.
+    ///
+    /// Layout:
+    /// ```txt
+    /// |--------|---------------|--------------|---------------|
+    /// | 0xFFE1 | 16 bit length | "Exif\0\0" | TIFF/IFD payload |
+    /// |--------|---------------|--------------|---------------|
+    /// ```
+    ///
+    pub fn write_exif(&mut self, tiff_payload: &[u8]) -> IOResult<()> {
+        if EXIF_TAG.len() + tiff_payload.len() > APP1_MAX_PAYLOAD {
+            return Err(IOError::new(
+                ErrorKind::InvalidInput,
+                "Exif payload exceeds the 65533 byte APP1 segment limit",
+            ));
+        }
+
+        self.write_marker(Marker::APP(1))?;
+        self.write_u16(2 + EXIF_TAG.len() as u16 + tiff_payload.len() as u16)?;
+        self.write(EXIF_TAG)?;
+        self.write(tiff_payload)?;
+
+        Ok(())
+    }
+
+    /// Append an ICC color profile as one or more APP2 segments.
+    ///
+    /// Profiles larger than [`ICC_MARKER_SIZE`] bytes are split into
+    /// multiple segments the way libjpeg-turbo's `jcicc.c`/`jdicc.c` do,
+    /// each carrying a 1-based sequence number and the total chunk count so
+    /// a decoder can reassemble the profile by concatenating the chunks in
+    /// order.
+    ///
+    /// Since a profile this large would need more than 255 chunks to encode
+    /// the way a 1-byte chunk count allows, this returns an
+    /// [`ErrorKind::InvalidInput`] error for profiles over 255 *
+    /// [`ICC_MARKER_SIZE`] bytes (~16.7MB) rather than panicking on
+    /// unusual-but-plausible caller-supplied input.
+    ///
+    /// Layout of a single segment:
+    /// ```txt
+    /// |--------|---------------|-----------------|----------|-------------|-------|
+    /// | 0xFFE2 | 16 bit length | "ICC_PROFILE\0" | sequence | chunk count | chunk |
+    /// |--------|---------------|-----------------|----------|-------------|-------|
+    /// ```
+    ///
+    pub fn write_icc_profile(&mut self, profile: &[u8]) -> IOResult<()> {
+        if profile.is_empty() {
+            return Ok(());
+        }
+
+        let chunks: Vec<&[u8]> = profile.chunks(ICC_MARKER_SIZE).collect();
+        if chunks.len() > 255 {
+            return Err(IOError::new(
+                ErrorKind::InvalidInput,
+                "ICC profile too large to split into APP2 segments",
+            ));
+        }
+
+        let num_chunks = chunks.len() as u8;
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            self.write_marker(Marker::APP(2))?;
+            self.write_u16(2 + ICC_PROFILE_TAG.len() as u16 + 2 + chunk.len() as u16)?;
+            self.write(ICC_PROFILE_TAG)?;
+            self.write_u8(i as u8 + 1)?;
+            self.write_u8(num_chunks)?;
+            self.write(chunk)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn icc_profile_exactly_one_marker_size_writes_a_single_segment() {
+        let profile = vec![0xAB; ICC_MARKER_SIZE];
+        let mut w = JfifWriter::new(Vec::new());
+
+        w.write_icc_profile(&profile).unwrap();
+
+        let buf = w.w;
+        assert_eq!(0xFF, buf[0]);
+        assert_eq!(0xE2, buf[1]);
+        let length = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+        assert_eq!(2 + ICC_PROFILE_TAG.len() + 2 + ICC_MARKER_SIZE, length);
+        assert_eq!(ICC_PROFILE_TAG.as_slice(), &buf[4..16]);
+        assert_eq!(1, buf[16], "sequence number");
+        assert_eq!(1, buf[17], "chunk count");
+        assert_eq!(profile.as_slice(), &buf[18..18 + ICC_MARKER_SIZE]);
+        // Exactly one segment: nothing left over for a second chunk.
+        assert_eq!(18 + ICC_MARKER_SIZE, buf.len());
+    }
+
+    #[test]
+    fn icc_profile_one_byte_over_a_marker_size_splits_into_two_segments() {
+        let profile = vec![0xCD; ICC_MARKER_SIZE + 1];
+        let mut w = JfifWriter::new(Vec::new());
+
+        w.write_icc_profile(&profile).unwrap();
+
+        let buf = w.w;
+        // First segment: full ICC_MARKER_SIZE chunk, sequence 1 of 2.
+        assert_eq!(1, buf[16]);
+        assert_eq!(2, buf[17]);
+        let first_segment_len = 4 + ICC_PROFILE_TAG.len() + 2 + ICC_MARKER_SIZE;
+        // Second segment: the one leftover byte, sequence 2 of 2.
+        let second = &buf[first_segment_len..];
+        assert_eq!(0xFF, second[0]);
+        assert_eq!(0xE2, second[1]);
+        let second_length = u16::from_be_bytes([second[2], second[3]]) as usize;
+        assert_eq!(2 + ICC_PROFILE_TAG.len() + 2 + 1, second_length);
+        assert_eq!(2, second[16]);
+        assert_eq!(2, second[17]);
+        assert_eq!(&[0xCD], &second[18..19]);
+        assert_eq!(first_segment_len + 19, buf.len());
+    }
+
+    #[test]
+    fn icc_profile_over_255_chunks_is_rejected() {
+        let profile = vec![0u8; ICC_MARKER_SIZE * 255 + 1];
+        let mut w = JfifWriter::new(Vec::new());
+
+        let err = w.write_icc_profile(&profile).unwrap_err();
+
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+    }
+
+    #[test]
+    fn empty_icc_profile_writes_nothing() {
+        let mut w = JfifWriter::new(Vec::new());
+
+        w.write_icc_profile(&[]).unwrap();
+
+        assert!(w.w.is_empty());
+    }
+
+    #[test]
+    fn exif_payload_at_the_app1_limit_is_written_whole() {
+        let payload = vec![0x11; APP1_MAX_PAYLOAD - EXIF_TAG.len()];
+        let mut w = JfifWriter::new(Vec::new());
+
+        w.write_exif(&payload).unwrap();
+
+        let buf = w.w;
+        assert_eq!(0xFF, buf[0]);
+        assert_eq!(0xE1, buf[1]);
+        let length = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+        assert_eq!(2 + APP1_MAX_PAYLOAD, length);
+        assert_eq!(EXIF_TAG.as_slice(), &buf[4..10]);
+        assert_eq!(payload.as_slice(), &buf[10..]);
+    }
+
+    #[test]
+    fn exif_payload_one_byte_over_the_app1_limit_is_rejected() {
+        let payload = vec![0x11; APP1_MAX_PAYLOAD - EXIF_TAG.len() + 1];
+        let mut w = JfifWriter::new(Vec::new());
+
+        let err = w.write_exif(&payload).unwrap_err();
+
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+    }
+
+    #[test]
+    fn restart_interval_segment_layout() {
+        let mut w = JfifWriter::new(Vec::new());
+
+        w.write_restart_interval(0x0123).unwrap();
+
+        assert_eq!(&[0xFF, 0xDD, 0x00, 0x04, 0x01, 0x23], w.w.as_slice());
+    }
+
+    #[test]
+    fn restart_marker_cycles_m_through_0_to_7() {
+        let mut w = JfifWriter::new(Vec::new());
+
+        w.write_restart_marker(0).unwrap();
+        w.write_restart_marker(7).unwrap();
+        w.write_restart_marker(8).unwrap();
+
+        assert_eq!(&[0xFF, 0xD0, 0xFF, 0xD7, 0xFF, 0xD0], w.w.as_slice());
+    }
 }
 